@@ -1,18 +1,382 @@
+use std::collections::HashMap;
+use std::io::BufReader;
 use std::time::Duration;
 
 use actix_web::{web, App, HttpServer};
 use actix_web_opentelemetry::RequestTracing;
-use opentelemetry::trace::{Span, Status, Tracer};
 use opentelemetry::{global, KeyValue};
-use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_otlp::{MetricsExporterBuilder, SpanExporterBuilder, WithExportConfig};
 use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::resource::{
     EnvResourceDetector, ResourceDetector, SdkProvidedResourceDetector, TelemetryResourceDetector,
 };
 use opentelemetry_sdk::trace::Config;
 use opentelemetry_sdk::{runtime, Resource};
 use serde::{Deserialize, Serialize};
-use tonic::metadata::MetadataMap;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Wire protocol to speak to the OTLP collector, selected via
+/// `OTEL_EXPORTER_OTLP_PROTOCOL`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OtlpProtocol {
+    Grpc,
+    HttpProtobuf,
+}
+
+impl OtlpProtocol {
+    fn from_env() -> Self {
+        match std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+            Ok(value) if value.eq_ignore_ascii_case("http/protobuf") => OtlpProtocol::HttpProtobuf,
+            Ok(value) if value.eq_ignore_ascii_case("http") => OtlpProtocol::HttpProtobuf,
+            Ok(value) if value.eq_ignore_ascii_case("grpc") => OtlpProtocol::Grpc,
+            // Default to gRPC to preserve existing behavior.
+            _ => OtlpProtocol::Grpc,
+        }
+    }
+}
+
+fn otlp_endpoint_from_env() -> Option<String> {
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()
+}
+
+fn otlp_timeout_from_env() -> Duration {
+    std::env::var("OTEL_EXPORTER_OTLP_TIMEOUT")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .unwrap_or_else(|| Duration::from_secs(10))
+}
+
+fn otlp_headers_from_env() -> HashMap<String, String> {
+    std::env::var("OTEL_EXPORTER_OTLP_HEADERS")
+        .ok()
+        .map(|raw| {
+            raw.split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Optional CA bundle and client certificate/key for reaching OTLP
+/// collectors behind a private CA or requiring mutual TLS. Falls back to
+/// native roots and no client identity when unset.
+struct TlsConfig {
+    ca_cert_path: Option<String>,
+    client_cert_path: Option<String>,
+    client_key_path: Option<String>,
+}
+
+impl TlsConfig {
+    fn from_env() -> Self {
+        Self {
+            ca_cert_path: std::env::var("FIBONACCI_OTLP_TLS_CA_CERT").ok(),
+            client_cert_path: std::env::var("FIBONACCI_OTLP_TLS_CLIENT_CERT").ok(),
+            client_key_path: std::env::var("FIBONACCI_OTLP_TLS_CLIENT_KEY").ok(),
+        }
+    }
+
+    fn build(&self) -> tonic::transport::ClientTlsConfig {
+        let mut tls_config = tonic::transport::ClientTlsConfig::new();
+
+        if let Some(ca_cert_path) = &self.ca_cert_path {
+            let certificate = load_ca_certificate(ca_cert_path)
+                .unwrap_or_else(|error| panic!("failed to load OTLP CA certificate from {ca_cert_path}: {error}"));
+            tls_config = tls_config.ca_certificate(certificate);
+        }
+
+        if let (Some(cert_path), Some(key_path)) = (&self.client_cert_path, &self.client_key_path) {
+            let identity = load_client_identity(cert_path, key_path).unwrap_or_else(|error| {
+                panic!("failed to load OTLP client identity from {cert_path} / {key_path}: {error}")
+            });
+            tls_config = tls_config.identity(identity);
+        }
+
+        tls_config
+    }
+
+    /// Build a `reqwest::Client` carrying the same CA/client identity as
+    /// [`TlsConfig::build`], for the HTTP/protobuf exporter. Returns `None`
+    /// when no TLS overrides are set, so the exporter's default client and
+    /// native roots are used instead.
+    fn build_http_client(&self) -> Option<reqwest::Client> {
+        if self.ca_cert_path.is_none() && self.client_cert_path.is_none() {
+            return None;
+        }
+
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(ca_cert_path) = &self.ca_cert_path {
+            let pem = std::fs::read(ca_cert_path)
+                .unwrap_or_else(|error| panic!("failed to read OTLP CA certificate from {ca_cert_path}: {error}"));
+            let certificate = reqwest::Certificate::from_pem(&pem)
+                .unwrap_or_else(|error| panic!("failed to parse OTLP CA certificate from {ca_cert_path}: {error}"));
+            // Trust only the configured CA here, not reqwest's built-in roots too.
+            builder = builder.add_root_certificate(certificate).tls_built_in_root_certs(false);
+        }
+
+        if let (Some(cert_path), Some(key_path)) = (&self.client_cert_path, &self.client_key_path) {
+            let mut identity_pem = std::fs::read(cert_path)
+                .unwrap_or_else(|error| panic!("failed to read OTLP client certificate from {cert_path}: {error}"));
+            let key_pem = std::fs::read(key_path)
+                .unwrap_or_else(|error| panic!("failed to read OTLP client key from {key_path}: {error}"));
+            identity_pem.extend_from_slice(&key_pem);
+            let identity = reqwest::Identity::from_pem(&identity_pem).unwrap_or_else(|error| {
+                panic!("failed to parse OTLP client identity from {cert_path} / {key_path}: {error}")
+            });
+            builder = builder.identity(identity);
+        }
+
+        Some(
+            builder
+                .build()
+                .expect("failed to build HTTP client for OTLP exporter"),
+        )
+    }
+}
+
+/// Read and validate a PEM-encoded CA bundle for [`TlsConfig`].
+fn load_ca_certificate(path: &str) -> Result<tonic::transport::Certificate, Box<dyn std::error::Error>> {
+    let pem = std::fs::read(path)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice())).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("no PEM certificates found in {path}").into());
+    }
+    Ok(tonic::transport::Certificate::from_pem(pem))
+}
+
+/// Read and validate a PEM-encoded client certificate/key pair for [`TlsConfig`].
+fn load_client_identity(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<tonic::transport::Identity, Box<dyn std::error::Error>> {
+    let cert_pem = std::fs::read(cert_path)?;
+    let key_pem = std::fs::read(key_path)?;
+
+    let certs =
+        rustls_pemfile::certs(&mut BufReader::new(cert_pem.as_slice())).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("no PEM certificates found in {cert_path}").into());
+    }
+    rustls_pemfile::private_key(&mut BufReader::new(key_pem.as_slice()))?
+        .ok_or_else(|| format!("no PEM private key found in {key_path}"))?;
+
+    Ok(tonic::transport::Identity::from_pem(cert_pem, key_pem))
+}
+
+/// Either exporter builder `opentelemetry_otlp` gives us, depending on
+/// `OtlpProtocol`. Lets `span_exporter_builder`/`metrics_exporter_builder`
+/// share the same match-on-protocol logic and just `.into()` the result.
+enum OtlpExporterBuilder {
+    Tonic(Box<opentelemetry_otlp::TonicExporterBuilder>),
+    Http(opentelemetry_otlp::HttpExporterBuilder),
+}
+
+impl From<OtlpExporterBuilder> for SpanExporterBuilder {
+    fn from(builder: OtlpExporterBuilder) -> Self {
+        match builder {
+            OtlpExporterBuilder::Tonic(builder) => (*builder).into(),
+            OtlpExporterBuilder::Http(builder) => builder.into(),
+        }
+    }
+}
+
+impl From<OtlpExporterBuilder> for MetricsExporterBuilder {
+    fn from(builder: OtlpExporterBuilder) -> Self {
+        match builder {
+            OtlpExporterBuilder::Tonic(builder) => (*builder).into(),
+            OtlpExporterBuilder::Http(builder) => builder.into(),
+        }
+    }
+}
+
+fn otlp_exporter_builder(protocol: OtlpProtocol, tls_config: &TlsConfig) -> OtlpExporterBuilder {
+    match protocol {
+        OtlpProtocol::Grpc => OtlpExporterBuilder::Tonic(Box::new(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_tls_config(tls_config.build()),
+        )),
+        OtlpProtocol::HttpProtobuf => {
+            let mut exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_timeout(otlp_timeout_from_env())
+                .with_headers(otlp_headers_from_env());
+            if let Some(endpoint) = otlp_endpoint_from_env() {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            if let Some(http_client) = tls_config.build_http_client() {
+                exporter = exporter.with_http_client(http_client);
+            }
+            OtlpExporterBuilder::Http(exporter)
+        }
+    }
+}
+
+fn span_exporter_builder(protocol: OtlpProtocol, tls_config: &TlsConfig) -> SpanExporterBuilder {
+    otlp_exporter_builder(protocol, tls_config).into()
+}
+
+fn metrics_exporter_builder(protocol: OtlpProtocol, tls_config: &TlsConfig) -> MetricsExporterBuilder {
+    otlp_exporter_builder(protocol, tls_config).into()
+}
+
+/// Application-supplied resource overrides, read from `FIBONACCI_SERVICE_NAME`,
+/// `FIBONACCI_SERVICE_VERSION`, `FIBONACCI_DEPLOYMENT_ENVIRONMENT`, and
+/// `FIBONACCI_RESOURCE_ATTRIBUTES` (comma-separated `key=value` pairs, same
+/// format as `OTEL_RESOURCE_ATTRIBUTES`).
+struct ResourceConfig {
+    service_name: Option<String>,
+    service_version: Option<String>,
+    deployment_environment: Option<String>,
+    extra_attributes: Vec<KeyValue>,
+}
+
+impl ResourceConfig {
+    fn from_env() -> Self {
+        let extra_attributes = std::env::var("FIBONACCI_RESOURCE_ATTRIBUTES")
+            .ok()
+            .map(|raw| {
+                raw.split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(key, value)| {
+                        KeyValue::new(key.trim().to_string(), value.trim().to_string())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            service_name: std::env::var("FIBONACCI_SERVICE_NAME").ok(),
+            service_version: std::env::var("FIBONACCI_SERVICE_VERSION").ok(),
+            deployment_environment: std::env::var("FIBONACCI_DEPLOYMENT_ENVIRONMENT").ok(),
+            extra_attributes,
+        }
+    }
+
+    fn into_key_values(self) -> Vec<KeyValue> {
+        let mut attributes = self.extra_attributes;
+        if let Some(service_name) = self.service_name {
+            attributes.push(KeyValue::new("service.name", service_name));
+        }
+        if let Some(service_version) = self.service_version {
+            attributes.push(KeyValue::new("service.version", service_version));
+        }
+        if let Some(deployment_environment) = self.deployment_environment {
+            attributes.push(KeyValue::new("deployment.environment", deployment_environment));
+        }
+        attributes
+    }
+}
+
+/// Merge the detected resource (SDK-provided, env, and telemetry detectors)
+/// with [`ResourceConfig`] overrides. `Resource::merge` lets the argument
+/// win on key conflicts (the same reason `env_resource`/`telemetry_resource`
+/// below can override the SDK's `unknown_service` default), so passing
+/// `overrides` as the argument is enough for it to take precedence.
+fn build_resource() -> Resource {
+    let sdk_provided_resource = SdkProvidedResourceDetector.detect(Duration::from_secs(0));
+    let env_resource = EnvResourceDetector::new().detect(Duration::from_secs(0));
+    let telemetry_resource = TelemetryResourceDetector.detect(Duration::from_secs(0));
+    let detected = sdk_provided_resource
+        .merge(&env_resource)
+        .merge(&telemetry_resource);
+
+    let overrides = ResourceConfig::from_env().into_key_values();
+    if overrides.is_empty() {
+        return detected;
+    }
+
+    detected.merge(&Resource::new(overrides))
+}
+
+/// Dropping this force-flushes and shuts down the meter provider, then the
+/// tracer provider.
+struct Guard {
+    meter_provider: SdkMeterProvider,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Err(error) = self.meter_provider.force_flush() {
+            tracing::error!(%error, "failed to flush OTel metrics on shutdown");
+        }
+        if let Err(error) = self.meter_provider.shutdown() {
+            tracing::error!(%error, "failed to shut down OTel meter provider");
+        }
+        global::shutdown_tracer_provider();
+    }
+}
+
+/// Bridges `tracing` spans/events to OTel via an `OpenTelemetryLayer`.
+/// Returns a [`Guard`] that must be kept alive until shutdown.
+fn init_telemetry() -> Guard {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let resource = build_resource();
+
+    let otlp_protocol = OtlpProtocol::from_env();
+    let tls_config = TlsConfig::from_env();
+
+    // `install_batch` builds the tracer provider internally and registers it globally.
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(span_exporter_builder(otlp_protocol, &tls_config))
+        .with_trace_config(Config::default().with_resource(resource.clone()))
+        .install_batch(runtime::Tokio)
+        .expect("failed to initialize the trace pipeline");
+
+    let metric_provider = opentelemetry_otlp::new_pipeline()
+        .metrics(opentelemetry_sdk::runtime::Tokio) // Use the Tokio runtime
+        .with_exporter(metrics_exporter_builder(otlp_protocol, &tls_config))
+        .with_resource(resource) // Reuse the resource from span setup
+        .build()
+        .expect("failed to initialize the metrics pipeline");
+
+    global::set_meter_provider(metric_provider.clone());
+
+    let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .try_init()
+        .expect("failed to install tracing subscriber");
+
+    Guard { meter_provider: metric_provider }
+}
+
+/// Wait for Ctrl-C or, on Unix, SIGTERM, then gracefully stop `server`.
+async fn wait_for_shutdown_signal(server: actix_web::dev::ServerHandle) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    server.stop(true).await;
+}
 
 #[derive(Deserialize)]
 struct FibonacciRequest {
@@ -36,17 +400,18 @@ fn is_zero(num: &i64) -> bool {
     *num == 0
 }
 
-fn is_empty(str: &String) -> bool {
+fn is_empty(str: &str) -> bool {
     str.is_empty()
 }
 
+#[tracing::instrument(skip(req))]
 async fn fibonacci(req: web::Query<FibonacciRequest>) -> web::Json<FibonacciResult> {
     let result_or_error = compute_fibonacci(req.n);
 
     match result_or_error {
         Ok(result) => web::Json(FibonacciResult {
             n: req.n,
-            result: result,
+            result,
             message: String::new(),
         }),
         Err(error) => web::Json(FibonacciResult {
@@ -57,22 +422,17 @@ async fn fibonacci(req: web::Query<FibonacciRequest>) -> web::Json<FibonacciResu
     }
 }
 
+#[tracing::instrument(skip(n), fields(fibonacci.n = n, fibonacci.result = tracing::field::Empty), err)]
 fn compute_fibonacci(n: i64) -> Result<i64, Box<dyn std::error::Error>> {
-    let tracer = global::tracer("fibonacci_server");
     let meter = global::meter("fibonacci_server_metric");
 
-    let mut span = tracer.span_builder("fibonacci").start(&tracer);
-
     let counter = meter.u64_counter("fibo_counter").init();
 
     counter.add(1, &[KeyValue::new("id", "1234")]);
 
-    span.set_attribute(KeyValue::new("fibonacci.n", n));
-
-    if n < 1 || n > 90 {
+    if !(1..=90).contains(&n) {
         let err_msg = "n must be between 1 and 90";
-        span.set_status(Status::error(err_msg));
-        // span.record_error(err);
+        tracing::error!(fibonacci.n = n, "{}", err_msg);
         return Err(Box::from(err_msg));
     }
 
@@ -86,59 +446,28 @@ fn compute_fibonacci(n: i64) -> Result<i64, Box<dyn std::error::Error>> {
             result = a + b;
             a = b;
             b = result;
-            i = i + 1;
+            i += 1;
         }
     }
-    span.set_attribute(KeyValue::new("fibonacci.result", result));
+    tracing::Span::current().record("fibonacci.result", result);
     Ok(result)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    global::set_text_map_propagator(TraceContextPropagator::new());
-
-    let sdk_provided_resource = SdkProvidedResourceDetector.detect(Duration::from_secs(0));
-    let env_resource = EnvResourceDetector::new().detect(Duration::from_secs(0));
-    let telemetry_resource = TelemetryResourceDetector.detect(Duration::from_secs(0));
-    let resource = sdk_provided_resource
-        .merge(&env_resource)
-        .merge(&telemetry_resource);
-
-    let tracer_provider = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(
-            opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_tls_config(tonic::transport::ClientTlsConfig::new().with_native_roots()),
-        )
-        .with_trace_config(Config::default().with_resource(resource.clone()))
-        .install_batch(runtime::Tokio)
-        .expect("failed to initialize the trace pipeline");
-
-    let metric_provider = opentelemetry_otlp::new_pipeline()
-        .metrics(opentelemetry_sdk::runtime::Tokio) // Use the Tokio runtime
-        .with_exporter(
-            opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_tls_config(tonic::transport::ClientTlsConfig::new().with_native_roots()), // Configure TLS
-        )
-        .with_resource(resource) // Reuse the resource from span setup
-        .build()
-        .expect("failed to initialize the metrics pipeline");
+    let _guard = init_telemetry();
 
-    global::set_meter_provider(metric_provider);
-    global::set_tracer_provider(tracer_provider);
-
-    HttpServer::new(|| {
+    let server = HttpServer::new(|| {
         App::new()
             .wrap(RequestTracing::new())
             .route("/fibonacci", web::get().to(fibonacci))
     })
     .bind(("0.0.0.0", 8080))?
-    .run()
-    .await?;
+    .run();
+
+    tokio::spawn(wait_for_shutdown_signal(server.handle()));
 
-    global::shutdown_tracer_provider();
+    server.await?;
 
-    Ok({})
+    Ok(())
 }